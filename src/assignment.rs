@@ -3,6 +3,7 @@ use std::iter::FusedIterator;
 use dimacs::{Lit, Sign};
 use fixedbitset::FixedBitSet;
 
+#[derive(Clone)]
 pub struct Assignment {
     buffer: FixedBitSet,
     assigned_literal_count: usize,
@@ -39,16 +39,32 @@ fn print_solve_result(result: SolveResult) {
             println!("0");
         }
 
-        SolveResult::Unsat => {
+        SolveResult::Unsat(_) => {
             println!("UNSAT");
         }
     }
 }
 
 fn main() {
-    let file = std::env::args().nth(1).expect("Please provide a file.");
+    let mut args = std::env::args().skip(1);
+    let file = args.next().expect("Please provide a file.");
 
-    let solver = initialize(file);
+    let mut proof_path = None;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--proof" => {
+                proof_path = Some(args.next().expect("--proof requires a file path"));
+            }
+            other => panic!("Unrecognized argument: {}", other),
+        }
+    }
+
+    let mut solver = initialize(file);
+
+    if let Some(path) = proof_path {
+        let proof_file = std::fs::File::create(path).expect("Failed to create proof file");
+        solver = solver.with_proof_writer(Box::new(proof_file));
+    }
 
     println!("Starting solver...");
     let result = solver.solve();
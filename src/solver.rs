@@ -1,9 +1,30 @@
-use std::collections::VecDeque;
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashSet, VecDeque};
+use std::io::Write;
 
-use dimacs::{Clause, Lit};
+use dimacs::{Clause, Lit, Sign};
 
 use crate::{assignment::Assignment, cnf::CnfFormula};
 
+/// The amount `var_inc` is multiplied by after every conflict, so that more
+/// recently active variables dominate the branching heuristic.
+const VAR_INC_GROWTH: f64 = 1.0 / 0.95;
+
+/// Once `var_inc` exceeds this, all activities are rescaled down to avoid
+/// floating point overflow.
+const ACTIVITY_RESCALE_THRESHOLD: f64 = 1e100;
+
+/// The restart threshold, in conflicts, is `luby(i) * RESTART_BASE`.
+const RESTART_BASE: u64 = 100;
+
+/// The number of conflicts before the first learnt-clause database
+/// reduction; this grows geometrically after each reduction.
+const INITIAL_REDUCE_THRESHOLD: u64 = 512;
+
+/// How much a learnt clause's activity is bumped when it is used as an
+/// antecedent during conflict analysis.
+const CLAUSE_ACTIVITY_INC: f64 = 1.0;
+
 pub struct Solver {
     /// The assignment to the variables.
     assignment: Assignment,
@@ -22,39 +43,262 @@ pub struct Solver {
 
     /// Storage for the antecedents of literals.
     variable_antecedent: Vec<Option<usize>>,
+
+    /// For each literal (indexed by `literal_code`), the indices of clauses in
+    /// `formula` that currently watch that literal, i.e. that need to be
+    /// re-examined when the literal is falsified.
+    watchers: Vec<Vec<usize>>,
+
+    /// The two literals each clause currently watches, indexed by clause index.
+    /// `None` for clauses with fewer than two literals, which are asserted as
+    /// top-level facts instead of being watched.
+    watch_lits: Vec<Option<[Lit; 2]>>,
+
+    /// Literals that have just been falsified and whose watcher lists still
+    /// need to be processed.
+    propagation_queue: VecDeque<Lit>,
+
+    /// A conflict discovered while adding a clause (e.g. two contradictory
+    /// unit clauses), to be surfaced the next time propagation runs.
+    pending_conflict: Option<usize>,
+
+    /// VSIDS activity score per variable, used to prioritize branching.
+    activity: Vec<f64>,
+
+    /// The amount `activity` is bumped by when a variable takes part in a
+    /// conflict; grows over time so recent conflicts matter more.
+    var_inc: f64,
+
+    /// A max-heap over variables keyed by `activity`, supporting lazy
+    /// deletion: entries for already-assigned variables are simply skipped
+    /// when popped.
+    activity_heap: BinaryHeap<HeapEntry>,
+
+    /// The polarity each variable last held when it was unassigned, used to
+    /// pick the sign of the next decision on that variable. Variables that
+    /// have never been assigned default to negative.
+    phases: Vec<bool>,
+
+    /// Scratch space for conflict-clause minimization, reused across
+    /// conflicts: which variables are currently marked as "covered" by the
+    /// learnt clause (or proven redundant).
+    seen: Vec<bool>,
+
+    /// Explicit work stack used by the recursive-minimization redundancy
+    /// check, to avoid real recursion.
+    ccmin_stack: Vec<Lit>,
+
+    /// Variables whose `seen` flag was set while probing redundancy, so it
+    /// can be reset after the probe (on failure) or after the whole
+    /// minimization pass (on success).
+    ccmin_clear: Vec<usize>,
+
+    /// Number of conflicts since the last restart.
+    conflicts_since_restart: u64,
+
+    /// Index into the Luby sequence for the next restart threshold.
+    luby_index: u64,
+
+    /// If set, every learnt clause is logged here as a DRAT addition line,
+    /// so an external checker can verify an `Unsat` result.
+    proof_writer: Option<Box<dyn Write>>,
+
+    /// Whether the clause at this index (parallel to `formula`) was learnt
+    /// through conflict analysis, as opposed to being part of the original
+    /// formula.
+    is_learnt: Vec<bool>,
+
+    /// The LBD (number of distinct decision levels among its literals) each
+    /// learnt clause had at the time it was learnt. Unused for original
+    /// clauses.
+    clause_lbd: Vec<u32>,
+
+    /// An activity score per clause, bumped when the clause is used as an
+    /// antecedent during conflict analysis; used as a tie-breaker when
+    /// reducing the learnt clause database.
+    clause_activity: Vec<f64>,
+
+    /// Total number of conflicts seen so far.
+    conflict_count: u64,
+
+    /// The conflict count at which the next learnt-clause database
+    /// reduction is due.
+    next_reduction: u64,
 }
 
 pub enum SolveResult {
     Sat(Assignment),
-    Unsat,
+    /// Unsatisfiable. The carried literals are the unsat core: the subset of
+    /// the assumptions (in `solve_under_assumptions`) responsible for the
+    /// conflict. Empty when the formula is unsatisfiable independent of any
+    /// assumptions.
+    Unsat(Vec<Lit>),
+}
+
+/// An entry in the VSIDS activity heap. Ordered by `activity` alone, so the
+/// heap can hold multiple stale entries for the same variable; the newest,
+/// highest-activity entry is always popped first.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct HeapEntry {
+    activity: f64,
+    variable: usize,
+}
+
+impl Eq for HeapEntry {}
+
+impl PartialOrd for HeapEntry {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for HeapEntry {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.activity
+            .partial_cmp(&other.activity)
+            .unwrap_or(Ordering::Equal)
+    }
+}
+
+/// The outcome of `conflict_analysis_and_backtrack`.
+enum ConflictOutcome {
+    /// The learnt clause was added and its asserting literal propagated;
+    /// search should resume at the carried decision level.
+    Backtracked(usize),
+    /// The conflict is implied by the assumptions alone: no ordinary
+    /// backtrack level can resolve it without unassigning an assumption.
+    /// The carried literals are the unsat core.
+    AssumptionConflict(Vec<Lit>),
+}
+
+/// The outcome of re-examining a clause that was watching a literal which
+/// just became false.
+enum WatchUpdate {
+    /// The clause now watches a different, non-false literal.
+    Moved,
+    /// The clause keeps its current watches (it is satisfied, or the other
+    /// watched literal was just propagated as a unit).
+    Kept,
+    /// Both watched literals are false; the clause is conflicting.
+    Conflict,
 }
 
 impl Solver {
     pub fn create(formula: CnfFormula) -> Solver {
         let num_vars = formula.num_variables();
-        let clauses = formula.clauses();
+        let clauses = Vec::from(formula.clauses());
 
-        Solver {
+        let mut solver = Solver {
             assignment: Assignment::new(num_vars),
             trail: VecDeque::new(),
             order: vec![None; num_vars],
             variable_antecedent: vec![None; num_vars],
             variable_decision_level: vec![None; num_vars],
-            formula: Vec::from(clauses),
+            formula: Vec::new(),
             variable_count: num_vars,
+            watchers: vec![Vec::new(); num_vars * 2],
+            watch_lits: Vec::new(),
+            propagation_queue: VecDeque::new(),
+            pending_conflict: None,
+            activity: vec![0.0; num_vars],
+            var_inc: 1.0,
+            activity_heap: (0..num_vars)
+                .map(|variable| HeapEntry {
+                    activity: 0.0,
+                    variable,
+                })
+                .collect(),
+            phases: vec![false; num_vars],
+            seen: vec![false; num_vars],
+            ccmin_stack: Vec::new(),
+            ccmin_clear: Vec::new(),
+            conflicts_since_restart: 0,
+            luby_index: 1,
+            proof_writer: None,
+            is_learnt: Vec::new(),
+            clause_lbd: Vec::new(),
+            clause_activity: Vec::new(),
+            conflict_count: 0,
+            next_reduction: INITIAL_REDUCE_THRESHOLD,
+        };
+
+        for clause in clauses {
+            solver.add_clause(clause, false, 0);
         }
+
+        solver
+    }
+
+    /// Log every learnt clause to `writer` as a DRAT proof, so that an
+    /// `Unsat` result can be independently verified with an external DRAT
+    /// checker.
+    pub fn with_proof_writer(mut self, writer: Box<dyn Write>) -> Solver {
+        self.proof_writer = Some(writer);
+        self
     }
 
     /// Run the solver to find a satisfying assignment or prove unsat.
-    pub fn solve(mut self) -> SolveResult {
+    pub fn solve(&mut self) -> SolveResult {
+        self.solve_under_assumptions(&[])
+    }
+
+    /// Run the solver under a set of assumed literals. The assumptions are
+    /// placed as forced decisions at the lowest decision levels, before any
+    /// ordinary branching. If the formula is unsatisfiable under the
+    /// assumptions, the returned `Unsat` carries the subset of assumptions
+    /// that caused the conflict (the unsat core).
+    ///
+    /// Learnt clauses, activities and phases are retained between calls, so
+    /// the solver can be reused across multiple queries with different
+    /// assumptions.
+    pub fn solve_under_assumptions(&mut self, assumptions: &[Lit]) -> SolveResult {
+        // A previous call may have left the trail at whatever level its
+        // search or assumptions reached; start every call from a clean
+        // slate. Learnt clauses, activities and phases are untouched by this.
+        self.backtrack_to_level(0);
+
         let mut decision_level = 0;
 
-        // Find top-level conflicts. If they exist, the formula is unsatisfiable.
-        let unit_propagate_result = self.unit_propagate(decision_level);
-        if unit_propagate_result.is_some() {
-            return SolveResult::Unsat;
+        // Find top-level conflicts. If they exist, the formula is
+        // unsatisfiable regardless of the assumptions.
+        if self.unit_propagate(decision_level).is_some() {
+            self.emit_proof_clause(&[]);
+            return SolveResult::Unsat(Vec::new());
         }
 
+        for &assumption in assumptions {
+            if self.assignment.is_true(assumption) {
+                continue;
+            }
+
+            if self.assignment.is_false(assumption) {
+                // The assumption was falsified by the formula together with
+                // the earlier assumptions; those belong in the core too.
+                let variable = self.literal_to_variable_index(assumption);
+                let mut core = match self.variable_antecedent[variable] {
+                    Some(antecedent) => self.analyze_assumption_conflict(antecedent),
+                    None => vec![negate(assumption)],
+                };
+                core.push(assumption);
+                self.backtrack_to_level(0);
+                return SolveResult::Unsat(core);
+            }
+
+            decision_level += 1;
+            self.assign_literal(assumption, decision_level, None);
+
+            if let Some(conflicting_clause) = self.unit_propagate(decision_level) {
+                let core = self.analyze_assumption_conflict(conflicting_clause);
+                self.backtrack_to_level(0);
+                return SolveResult::Unsat(core);
+            }
+        }
+
+        // Assumptions occupy the decision levels below this point; a restart
+        // must never backtrack past them, or the search would continue
+        // without the literals it was asked to assume.
+        let assumption_level = decision_level;
+
         while !self.all_variables_assigned() {
             let picked_variable = self.pick_branching_variable();
             decision_level += 1;
@@ -74,12 +318,33 @@ impl Solver {
 
                     // If the conflict was at the top level, the formula is unsatisfiable
                     if decision_level == 0 {
-                        return SolveResult::Unsat;
+                        self.emit_proof_clause(&[]);
+                        return SolveResult::Unsat(Vec::new());
                     }
 
-                    decision_level =
-                        self.conflict_analysis_and_backtrack(conflicting_clause, decision_level);
+                    decision_level = match self.conflict_analysis_and_backtrack(
+                        conflicting_clause,
+                        decision_level,
+                        assumption_level,
+                    ) {
+                        ConflictOutcome::Backtracked(new_decision_level) => new_decision_level,
+                        ConflictOutcome::AssumptionConflict(core) => {
+                            return SolveResult::Unsat(core);
+                        }
+                    };
                     println!("\tBacktracking to level {}", decision_level);
+
+                    self.conflicts_since_restart += 1;
+                    if decision_level > assumption_level
+                        && self.conflicts_since_restart >= luby(self.luby_index) * RESTART_BASE
+                    {
+                        println!("\tRestarting.");
+                        self.backtrack_to_level(assumption_level);
+                        decision_level = assumption_level;
+                        self.conflicts_since_restart = 0;
+                        self.luby_index += 1;
+                        break;
+                    }
                 } else {
                     // No conflict was derived, continue with search.
                     break;
@@ -89,13 +354,45 @@ impl Solver {
 
         // If we reached here, all variables were successfully assigned, and the
         // formula is satisfiable
-        SolveResult::Sat(self.assignment)
+        SolveResult::Sat(self.assignment.clone())
     }
 
     fn all_variables_assigned(&self) -> bool {
         self.variable_count == self.assignment.size()
     }
 
+    /// Register a clause with the watched-literal scheme, or assert it as a
+    /// top-level fact if it has fewer than two literals.
+    fn add_clause(&mut self, clause: Clause, is_learnt: bool, lbd: u32) {
+        let clause_idx = self.formula.len();
+        let lits = Vec::from(clause.lits());
+        self.formula.push(clause);
+        self.is_learnt.push(is_learnt);
+        self.clause_lbd.push(lbd);
+        self.clause_activity.push(0.0);
+
+        if lits.len() < 2 {
+            self.watch_lits.push(None);
+
+            if let Some(&literal) = lits.first() {
+                if self.assignment.is_false(literal) {
+                    self.pending_conflict = Some(clause_idx);
+                } else if self.assignment.is_unassigned(literal) {
+                    self.assign_literal(literal, 0, Some(clause_idx));
+                }
+            }
+
+            return;
+        }
+
+        let watched = [lits[0], lits[1]];
+        self.watch_lits.push(Some(watched));
+        let code0 = self.literal_code(watched[0]);
+        let code1 = self.literal_code(watched[1]);
+        self.watchers[code0].push(clause_idx);
+        self.watchers[code1].push(clause_idx);
+    }
+
     /// Run boolean constraint propagation on the formula.
     ///
     /// If propagation causes a clause to be conflicting, this method returns
@@ -105,50 +402,71 @@ impl Solver {
     /// If propagation finishes without identifying a conflict, None is
     /// returned.
     fn unit_propagate(&mut self, decision_level: usize) -> Option<usize> {
-        let mut unit_clause_found = true;
-        while unit_clause_found {
-            unit_clause_found = false;
+        if let Some(conflict) = self.pending_conflict.take() {
+            return Some(conflict);
+        }
 
-            // Iterate over all clauses if no unit clause has been found so far
-            'clause: for clause_idx in 0..self.formula.len() {
-                let clause = &self.formula[clause_idx];
+        while let Some(false_literal) = self.propagation_queue.pop_front() {
+            let code = self.literal_code(false_literal);
+            let mut i = 0;
 
-                let mut unassigned_literal = None;
+            while i < self.watchers[code].len() {
+                let clause_idx = self.watchers[code][i];
 
-                for &literal in clause.lits() {
-                    if self.assignment.is_true(literal) {
-                        // The clause is satisfied.
-                        continue 'clause;
+                match self.update_watch(clause_idx, false_literal, decision_level) {
+                    WatchUpdate::Moved => {
+                        self.watchers[code].swap_remove(i);
                     }
-
-                    if self.assignment.is_unassigned(literal) && unassigned_literal.is_none() {
-                        // First unassigned literal we encountered for this clause.
-                        unassigned_literal = Some(literal);
-                    } else if self.assignment.is_unassigned(literal) {
-                        // More than 1 unassigned literal, so we cannot propagate.
-                        continue 'clause;
+                    WatchUpdate::Kept => {
+                        i += 1;
+                    }
+                    WatchUpdate::Conflict => {
+                        self.propagation_queue.clear();
+                        return Some(clause_idx);
                     }
                 }
+            }
+        }
 
-                if let Some(literal) = unassigned_literal {
-                    unit_clause_found = true;
+        None
+    }
 
-                    self.assign_literal(literal, decision_level, Some(clause_idx));
-                }
-            }
+    /// Re-examine `clause_idx`, which was watching `false_literal` and just
+    /// lost it. Tries to move the watch to a non-false literal; if none is
+    /// available, either propagates the other watched literal or reports a
+    /// conflict.
+    fn update_watch(
+        &mut self,
+        clause_idx: usize,
+        false_literal: Lit,
+        decision_level: usize,
+    ) -> WatchUpdate {
+        let [w0, w1] = self.watch_lits[clause_idx].expect("watched clause must have two watches");
+        let other_watch = if w0 == false_literal { w1 } else { w0 };
+
+        if self.assignment.is_true(other_watch) {
+            return WatchUpdate::Kept;
         }
 
-        let possible_conflict = self.formula.iter().enumerate().find(|(_, clause)| {
-            clause
-                .lits()
-                .iter()
-                .all(|&literal| self.assignment.is_false(literal))
-        });
+        let replacement = self
+            .formula[clause_idx]
+            .lits()
+            .iter()
+            .copied()
+            .find(|&lit| lit != w0 && lit != w1 && !self.assignment.is_false(lit));
+
+        if let Some(new_watch) = replacement {
+            self.watch_lits[clause_idx] = Some([new_watch, other_watch]);
+            let code = self.literal_code(new_watch);
+            self.watchers[code].push(clause_idx);
+            return WatchUpdate::Moved;
+        }
 
-        if let Some((conflict_idx, _)) = possible_conflict {
-            Some(conflict_idx)
+        if self.assignment.is_unassigned(other_watch) {
+            self.assign_literal(other_watch, decision_level, Some(clause_idx));
+            WatchUpdate::Kept
         } else {
-            None
+            WatchUpdate::Conflict
         }
     }
 
@@ -159,6 +477,14 @@ impl Solver {
         literal.var().to_u64() as usize - 1
     }
 
+    /// Map a literal to a code in `0..2*variable_count`, used to index the
+    /// watch lists.
+    fn literal_code(&self, literal: Lit) -> usize {
+        let variable = self.literal_to_variable_index(literal);
+        let sign_bit = if literal.sign() == Sign::Pos { 0 } else { 1 };
+        2 * variable + sign_bit
+    }
+
     /// Assign a literal the value 'true', and record at which decision level
     /// the literal was assigned, as well as its antecedent.
     fn assign_literal(&mut self, literal: Lit, decision_level: usize, antecedent: Option<usize>) {
@@ -170,25 +496,39 @@ impl Solver {
 
         self.variable_decision_level[variable] = Some(decision_level); // set decision level
         self.variable_antecedent[variable] = antecedent; // set antecedent
+
+        // The negation of the literal we just made true has just become
+        // false, so its watchers need to be re-examined.
+        self.propagation_queue.push_back(negate(literal));
     }
 
     /// Unassign the given literal, as well as update the bookkeeping in place
     /// for each variable.
     fn unassign_literal(&mut self, literal: Lit) {
         let literal_index = self.literal_to_variable_index(literal);
+        self.phases[literal_index] = literal.sign() == Sign::Pos;
         self.assignment.unassign(literal);
         self.order[literal_index] = None;
         self.variable_decision_level[literal_index] = None; // unassign decision level
         self.variable_antecedent[literal_index] = None; // unassign antecedent
+
+        // The variable is eligible for branching again; make sure it is
+        // reachable from the top of the activity heap.
+        self.activity_heap.push(HeapEntry {
+            activity: self.activity[literal_index],
+            variable: literal_index,
+        });
     }
 
     /// Analyze the conflict, which occurs in the clause with index
-    /// 'conflicting_clause'.
+    /// 'conflicting_clause'. `assumption_level` is the decision level up to
+    /// which assumptions were placed; search must never backtrack past it.
     fn conflict_analysis_and_backtrack(
         &mut self,
         conflicting_clause: usize,
         conflict_decision_level: usize,
-    ) -> usize {
+        assumption_level: usize,
+    ) -> ConflictOutcome {
         // the new clause to learn, initialized with the antecedent of the conflict
         let mut learnt_clause = Vec::from(self.formula[conflicting_clause].lits());
 
@@ -208,30 +548,227 @@ impl Solver {
                 .max_by_key(|&&lit| self.assignment_order(lit));
 
             match resolving_literal {
-                Some(&lit) => self.resolve(&mut learnt_clause, lit),
+                Some(&lit) => {
+                    self.bump_activity(self.literal_to_variable_index(lit));
+                    self.resolve(&mut learnt_clause, lit);
+                }
                 None => {}
             }
         }
 
-        self.formula.push(Clause::from_vec(learnt_clause));
+        for &lit in &learnt_clause {
+            self.bump_activity(self.literal_to_variable_index(lit));
+        }
+        self.var_inc *= VAR_INC_GROWTH;
 
-        let backtracked_decision_level = self
-            .formula
-            .last()
-            .unwrap()
-            .lits()
+        self.minimize_learnt_clause(&mut learnt_clause, conflict_decision_level);
+
+        // Move the asserting (1-UIP) literal to index 0, and the literal
+        // with the next-highest decision level to index 1, so that the two
+        // watches `add_clause` picks are exactly the pair that makes this
+        // clause unit once we backtrack below `conflict_decision_level`.
+        let asserting_pos = learnt_clause
+            .iter()
+            .position(|&lit| self.decision_level(lit) == Some(conflict_decision_level))
+            .expect("learnt clause must have exactly one literal at the conflict level");
+        learnt_clause.swap(0, asserting_pos);
+
+        let backtracked_decision_level = learnt_clause[1..]
             .iter()
             .map(|&lit| self.decision_level(lit).unwrap())
-            .filter(|&level| level != conflict_decision_level)
             .max()
             .unwrap_or(0);
 
+        if backtracked_decision_level < assumption_level {
+            // The conflict is implied by the assumptions alone: every
+            // decision below the asserting literal is itself an assumption,
+            // so there is no search backtrack that resolves this without
+            // unassigning one of them. The trail still holds every
+            // antecedent `conflicting_clause` depends on, so analyze it the
+            // same way the pre-search assumption-conflict paths do.
+            let core = self.analyze_assumption_conflict(conflicting_clause);
+            self.backtrack_to_level(0);
+            return ConflictOutcome::AssumptionConflict(core);
+        }
+
+        if learnt_clause.len() > 1 {
+            let second_pos = 1 + learnt_clause[1..]
+                .iter()
+                .position(|&lit| self.decision_level(lit) == Some(backtracked_decision_level))
+                .unwrap();
+            learnt_clause.swap(1, second_pos);
+        }
+
+        self.emit_proof_clause(&learnt_clause);
+
+        let lbd = self.compute_lbd(&learnt_clause);
+        let asserting_literal = learnt_clause[0];
+        let is_unit = learnt_clause.len() == 1;
+
         self.backtrack_to_level(backtracked_decision_level);
-        backtracked_decision_level
+
+        self.add_clause(Clause::from_vec(learnt_clause), true, lbd);
+
+        if !is_unit {
+            // Unit clauses are asserted directly by `add_clause`; longer
+            // clauses only have their watches registered, so the asserting
+            // literal needs to be propagated explicitly here.
+            let new_clause_idx = self.formula.len() - 1;
+            self.assign_literal(
+                asserting_literal,
+                backtracked_decision_level,
+                Some(new_clause_idx),
+            );
+        }
+
+        // The just-learnt clause is already locked against removal: it is
+        // now the antecedent of `asserting_literal` (or, if unit, was
+        // asserted directly by `add_clause`), and `reduce_learnt_clauses`
+        // never removes a clause that is a current antecedent.
+        self.conflict_count += 1;
+        self.maybe_reduce_learnt_clauses();
+
+        ConflictOutcome::Backtracked(backtracked_decision_level)
+    }
+
+    /// Analyze a conflict raised while propagating assumptions, before any
+    /// ordinary branching has happened. Unlike `conflict_analysis_and_backtrack`,
+    /// this resolves the clause all the way down to only decision literals
+    /// (i.e. assumptions), rather than stopping at the first UIP, since there
+    /// is no search to resume afterwards. The negation of each remaining
+    /// literal is part of the unsat core.
+    fn analyze_assumption_conflict(&mut self, conflicting_clause: usize) -> Vec<Lit> {
+        let mut clause = Vec::from(self.formula[conflicting_clause].lits());
+
+        loop {
+            let resolving_literal = clause.iter().copied().find(|&lit| {
+                self.variable_antecedent[self.literal_to_variable_index(lit)].is_some()
+            });
+
+            match resolving_literal {
+                Some(lit) => self.resolve(&mut clause, lit),
+                None => break,
+            }
+        }
+
+        clause.into_iter().map(negate).collect()
+    }
+
+    /// Compute the LBD (Literal Block Distance) of a clause: the number of
+    /// distinct decision levels among its literals.
+    fn compute_lbd(&self, clause: &[Lit]) -> u32 {
+        let mut levels = clause
+            .iter()
+            .map(|&lit| self.decision_level(lit).unwrap_or(0))
+            .collect::<Vec<_>>();
+        levels.sort_unstable();
+        levels.dedup();
+        levels.len() as u32
+    }
+
+    /// Run a learnt-clause database reduction if enough conflicts have
+    /// accumulated since the last one, then push the next threshold out
+    /// geometrically.
+    fn maybe_reduce_learnt_clauses(&mut self) {
+        if self.conflict_count < self.next_reduction {
+            return;
+        }
+
+        self.reduce_learnt_clauses();
+        self.next_reduction += self.next_reduction / 2;
+    }
+
+    /// Delete roughly the worse half of the learnt clauses, ranked by LBD
+    /// (higher is worse) and, as a tie-breaker, activity (lower is worse).
+    /// Clauses currently serving as an antecedent for an assigned variable
+    /// are never deleted.
+    fn reduce_learnt_clauses(&mut self) {
+        let locked: HashSet<usize> = self.variable_antecedent.iter().filter_map(|&a| a).collect();
+
+        let mut candidates: Vec<usize> = (0..self.formula.len())
+            .filter(|&idx| self.is_learnt[idx] && !locked.contains(&idx))
+            .collect();
+
+        candidates.sort_by(|&a, &b| {
+            self.clause_lbd[b].cmp(&self.clause_lbd[a]).then_with(|| {
+                self.clause_activity[a]
+                    .partial_cmp(&self.clause_activity[b])
+                    .unwrap_or(Ordering::Equal)
+            })
+        });
+
+        let remove_count = candidates.len() / 2;
+        if remove_count == 0 {
+            return;
+        }
+
+        let to_remove: HashSet<usize> = candidates.into_iter().take(remove_count).collect();
+        self.compact_clauses(&to_remove);
+    }
+
+    /// Physically remove the clauses in `to_remove` from `formula` and all
+    /// its parallel arrays, then rewrite every stored clause index (in
+    /// `variable_antecedent` and the watch lists) to match the new, compacted
+    /// positions.
+    fn compact_clauses(&mut self, to_remove: &HashSet<usize>) {
+        for &idx in to_remove {
+            let lits = Vec::from(self.formula[idx].lits());
+            self.emit_proof_deletion(&lits);
+        }
+
+        let old_len = self.formula.len();
+        let mut index_map: Vec<Option<usize>> = vec![None; old_len];
+
+        let mut new_formula = Vec::with_capacity(old_len - to_remove.len());
+        let mut new_watch_lits = Vec::with_capacity(new_formula.capacity());
+        let mut new_is_learnt = Vec::with_capacity(new_formula.capacity());
+        let mut new_lbd = Vec::with_capacity(new_formula.capacity());
+        let mut new_activity = Vec::with_capacity(new_formula.capacity());
+
+        for (old_idx, clause) in std::mem::take(&mut self.formula).into_iter().enumerate() {
+            if to_remove.contains(&old_idx) {
+                continue;
+            }
+
+            index_map[old_idx] = Some(new_formula.len());
+            new_formula.push(clause);
+            new_watch_lits.push(self.watch_lits[old_idx]);
+            new_is_learnt.push(self.is_learnt[old_idx]);
+            new_lbd.push(self.clause_lbd[old_idx]);
+            new_activity.push(self.clause_activity[old_idx]);
+        }
+
+        self.formula = new_formula;
+        self.watch_lits = new_watch_lits;
+        self.is_learnt = new_is_learnt;
+        self.clause_lbd = new_lbd;
+        self.clause_activity = new_activity;
+
+        for antecedent in self.variable_antecedent.iter_mut() {
+            if let Some(old_idx) = *antecedent {
+                *antecedent = index_map[old_idx];
+            }
+        }
+
+        for watch_list in self.watchers.iter_mut() {
+            watch_list.retain_mut(|clause_idx| match index_map[*clause_idx] {
+                Some(new_idx) => {
+                    *clause_idx = new_idx;
+                    true
+                }
+                None => false,
+            });
+        }
     }
 
     /// Undo variable assignments above the given decision level.
     fn backtrack_to_level(&mut self, target_decision_level: usize) {
+        // Any literal still queued for propagation was made false by an
+        // assignment we are about to undo; once undone it may no longer be
+        // false (or even assigned), so propagating it further would be
+        // unsound.
+        self.propagation_queue.clear();
+
         loop {
             let (literal, decision_level) = match self.trail.back() {
                 Some(&entry) => entry,
@@ -247,32 +784,60 @@ impl Solver {
         }
     }
 
-    /// Pick the next literal to assign in the search. This will return a
-    /// literal whose value is not yet assigned.
-    fn pick_branching_variable(&self) -> Lit {
-        // This is very naive and inefficient, but should work for very small
-        // instances. Just pick the first unassigned literal in a clause which
-        // is not yet satisfied.
+    /// Pick the next literal to assign in the search: the currently
+    /// unassigned variable with the highest VSIDS activity, signed according
+    /// to its saved phase.
+    fn pick_branching_variable(&mut self) -> Lit {
+        while let Some(HeapEntry { variable, .. }) = self.activity_heap.pop() {
+            let probe = Lit::from_i64((variable + 1) as i64);
 
-        for clause in self.formula.iter() {
-            if self.is_sat(clause) {
-                continue;
-            }
-
-            for &literal in clause.lits() {
-                if self.assignment.is_unassigned(literal) {
-                    return literal;
-                }
+            if self.assignment.is_unassigned(probe) {
+                let sign = if self.phases[variable] { 1 } else { -1 };
+                return Lit::from_i64(sign * (variable + 1) as i64);
             }
         }
 
         panic!("Could not find branching variable.")
     }
 
+    /// Bump a variable's VSIDS activity because it took part in a conflict,
+    /// rescaling all activities if `var_inc` has grown too large.
+    fn bump_activity(&mut self, variable: usize) {
+        self.activity[variable] += self.var_inc;
+        self.activity_heap.push(HeapEntry {
+            activity: self.activity[variable],
+            variable,
+        });
+
+        if self.var_inc > ACTIVITY_RESCALE_THRESHOLD {
+            self.rescale_activity();
+        }
+    }
+
+    /// Scale all activities (and `var_inc`) down together, and rebuild the
+    /// heap so its entries reflect the rescaled values.
+    fn rescale_activity(&mut self) {
+        for activity in self.activity.iter_mut() {
+            *activity *= 1.0 / ACTIVITY_RESCALE_THRESHOLD;
+        }
+        self.var_inc *= 1.0 / ACTIVITY_RESCALE_THRESHOLD;
+
+        self.activity_heap = self
+            .activity
+            .iter()
+            .enumerate()
+            .map(|(variable, &activity)| HeapEntry { activity, variable })
+            .collect();
+    }
+
     fn resolve(&mut self, input_clause: &mut Vec<Lit>, literal: Lit) {
         let literal_index = self.literal_to_variable_index(literal);
         let antecedent = self.variable_antecedent[literal_index].unwrap();
 
+        if self.is_learnt[antecedent] {
+            self.clause_activity[antecedent] += CLAUSE_ACTIVITY_INC;
+        }
+
         // Add the antecedent to the input clause.
         input_clause.extend(self.formula[antecedent].lits());
 
@@ -296,12 +861,109 @@ impl Solver {
         input_clause.dedup();
     }
 
-    /// Indicate whether a clause is satisfied under the current assignment.
-    fn is_sat(&self, clause: &Clause) -> bool {
-        clause
-            .lits()
-            .iter()
-            .any(|&lit| self.assignment.is_true(lit))
+    /// Write `clause` to the DRAT proof, if one is being recorded, as a
+    /// space-separated DIMACS addition line terminated by `0`.
+    fn emit_proof_clause(&mut self, clause: &[Lit]) {
+        if let Some(writer) = self.proof_writer.as_mut() {
+            for &lit in clause {
+                let _ = write!(writer, "{} ", lit_to_int(lit));
+            }
+            let _ = writeln!(writer, "0");
+        }
+    }
+
+    /// Write `clause` to the DRAT proof, if one is being recorded, as a
+    /// `d`-prefixed deletion line. Must be emitted before a clause is
+    /// physically removed from `formula`, so a checker replaying the proof
+    /// stops considering it for resolution at the same point the solver did.
+    fn emit_proof_deletion(&mut self, clause: &[Lit]) {
+        if let Some(writer) = self.proof_writer.as_mut() {
+            let _ = write!(writer, "d ");
+            for &lit in clause {
+                let _ = write!(writer, "{} ", lit_to_int(lit));
+            }
+            let _ = writeln!(writer, "0");
+        }
+    }
+
+    /// Shrink a learnt clause via recursive self-subsumption: a non-asserting
+    /// literal can be dropped if every literal in its antecedent clause is
+    /// already covered by the learnt clause (directly, or transitively via
+    /// this same check).
+    fn minimize_learnt_clause(&mut self, clause: &mut Vec<Lit>, conflict_decision_level: usize) {
+        for &lit in clause.iter() {
+            let variable = self.literal_to_variable_index(lit);
+            self.seen[variable] = true;
+        }
+
+        let mut minimized = Vec::with_capacity(clause.len());
+        for &lit in clause.iter() {
+            let is_asserting = self.decision_level(lit) == Some(conflict_decision_level);
+
+            if is_asserting || !self.is_redundant(lit) {
+                minimized.push(lit);
+            }
+        }
+
+        for &lit in clause.iter() {
+            let variable = self.literal_to_variable_index(lit);
+            self.seen[variable] = false;
+        }
+        for &var in &self.ccmin_clear {
+            self.seen[var] = false;
+        }
+        self.ccmin_clear.clear();
+
+        *clause = minimized;
+    }
+
+    /// Check whether `literal` is redundant in the learnt clause being
+    /// minimized: every literal in its antecedent clause must be `seen`
+    /// (covered by the learnt clause, or already proven redundant) or itself
+    /// recursively redundant. A literal with no antecedent is a decision and
+    /// is never redundant.
+    fn is_redundant(&mut self, literal: Lit) -> bool {
+        self.ccmin_stack.clear();
+        self.ccmin_stack.push(literal);
+        let probe_start = self.ccmin_clear.len();
+
+        while let Some(lit) = self.ccmin_stack.pop() {
+            let variable = self.literal_to_variable_index(lit);
+
+            let antecedent = match self.variable_antecedent[variable] {
+                Some(antecedent) => antecedent,
+                None => {
+                    // A decision literal: this probe failed, undo its marks.
+                    for &var in &self.ccmin_clear[probe_start..] {
+                        self.seen[var] = false;
+                    }
+                    self.ccmin_clear.truncate(probe_start);
+                    return false;
+                }
+            };
+
+            for &antecedent_lit in self.formula[antecedent].lits() {
+                let antecedent_var = self.literal_to_variable_index(antecedent_lit);
+
+                if antecedent_var == variable || self.seen[antecedent_var] {
+                    continue;
+                }
+
+                if self.decision_level(antecedent_lit) == Some(0) {
+                    // Level-0 facts are implied unconditionally, so they are
+                    // always removable.
+                    self.seen[antecedent_var] = true;
+                    self.ccmin_clear.push(antecedent_var);
+                    continue;
+                }
+
+                self.seen[antecedent_var] = true;
+                self.ccmin_clear.push(antecedent_var);
+                self.ccmin_stack.push(antecedent_lit);
+            }
+        }
+
+        true
     }
 
     /// Get the decision level at which the given literal was assigned, or None
@@ -320,9 +982,27 @@ impl Solver {
     }
 }
 
-fn lit_to_int(lit: Lit) -> i32 {
-    use dimacs::Sign;
+/// Return the negation of the given literal.
+fn negate(literal: Lit) -> Lit {
+    Lit::from_i64(-(lit_to_int(literal) as i64))
+}
+
+/// The Luby sequence: 1, 1, 2, 1, 1, 2, 4, 1, 1, 2, 1, 1, 2, 4, 8, ...
+/// used to schedule restarts.
+fn luby(i: u64) -> u64 {
+    let mut k = 1u32;
+    while (1u64 << k) - 1 < i {
+        k += 1;
+    }
 
+    if (1u64 << k) - 1 == i {
+        return 1u64 << (k - 1);
+    }
+
+    luby(i - ((1u64 << (k - 1)) - 1))
+}
+
+fn lit_to_int(lit: Lit) -> i32 {
     let num = lit.var().to_u64() as i32;
 
     if lit.sign() == Sign::Pos {